@@ -5,29 +5,91 @@ use std::{
     net::{Ipv4Addr, SocketAddr, TcpListener, ToSocketAddrs},
     str,
     sync::{
-        mpsc::{self, Receiver, RecvError, Sender},
-        Mutex, MutexGuard,
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use mio::net::TcpStream;
+use mio::{event::Event, net::TcpStream, Events, Interest, Poll, Registry, Token, Waker};
 
 use crate::{error::*, E};
 
 const MAX_WORKER_THREADS: usize = 128;
-const WORKER_DELAY: Duration = Duration::from_millis(1);
+const EVENTS_CAPACITY: usize = 1024;
 const BUFFER_SIZE: usize = 4096;
 const GET: &[u8] = b"GET";
+const CONNECT: &[u8] = b"CONNECT";
 const HOST_HEADER: &str = "host:";
+const ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
 
-pub type Hosts = HashSet<String>;
+// Reserved for the cross-thread wake-up; connection tokens never reach it.
+const WAKER: Token = Token(usize::MAX);
+
+// Routing set: exact host names kept in a fast `HashSet`, with domain
+// suffixes scanned only when the exact lookup misses.
+pub struct Hosts {
+    exact: HashSet<String>,
+    suffixes: Vec<String>,
+}
+
+impl Default for Hosts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hosts {
+    pub fn new() -> Self {
+        Hosts {
+            exact: HashSet::new(),
+            suffixes: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, pattern: &str) {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return;
+        }
+
+        // `*.example.com` and `.example.com` both cover any subdomain.
+        if let Some(suffix) = pattern.strip_prefix("*.").or_else(|| pattern.strip_prefix('.')) {
+            self.suffixes.push(suffix.to_owned());
+        } else {
+            self.exact.insert(pattern.to_owned());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffixes.is_empty()
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        if self.exact.contains(host) {
+            return true;
+        }
+
+        self.suffixes
+            .iter()
+            .any(|suffix| host == suffix || matches!(host.strip_suffix(suffix), Some(p) if p.ends_with('.')))
+    }
+}
+
+// Tuning knobs that don't vary per connection, grouped so `start` stays within
+// a sane argument count.
+pub struct Config {
+    pub dns_ttl: Duration,
+    pub dns_cache_size: usize,
+    pub max_header_size: usize,
+}
 
 pub struct App {
     proxy: SocketAddr,
     hosts: Hosts,
     debug: bool,
+    max_header_size: usize,
     dns: Dns,
 }
 
@@ -38,12 +100,14 @@ impl App {
         port: u16,
         mut worker_threads: usize,
         debug: bool,
+        config: Config,
     ) -> Result<(), AppError> {
         let app = &Self {
             proxy,
             hosts,
             debug,
-            dns: Dns::new(),
+            max_header_size: config.max_header_size,
+            dns: Dns::new(config.dns_ttl, config.dns_cache_size),
         };
 
         worker_threads = match worker_threads {
@@ -59,10 +123,14 @@ impl App {
             println!();
             println!("Hosts:");
 
-            for host in &app.hosts {
+            for host in &app.hosts.exact {
                 println!("  {host}");
             }
 
+            for suffix in &app.hosts.suffixes {
+                println!("  *.{suffix}");
+            }
+
             println!();
             println!("Worker threads: {worker_threads}");
             println!("Listen port: {port}");
@@ -70,20 +138,22 @@ impl App {
         }
 
         thread::scope(|scope| {
-            let senders = (0..worker_threads)
-                .map(|_| {
-                    let (sender, receiver) = mpsc::channel();
-                    scope.spawn(move || Worker::run(app, receiver));
-                    sender
-                })
-                .collect::<Vec<_>>();
+            let mut senders = Vec::with_capacity(worker_threads);
+
+            for _ in 0..worker_threads {
+                let poll = Poll::new()?;
+                let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+                let (sender, receiver) = mpsc::channel();
+                scope.spawn(move || Worker::run(app, receiver, poll));
+                senders.push((sender, waker));
+            }
 
             println!("Proxy is running.");
             println!();
 
             loop {
-                for sender in &senders {
-                    app.accept(&server, sender)?;
+                for (sender, waker) in &senders {
+                    app.accept(&server, sender, waker)?;
                 }
             }
         })
@@ -93,6 +163,7 @@ impl App {
         &'a self,
         server: &TcpListener,
         sender: &Sender<Connection<'a>>,
+        waker: &Waker,
     ) -> Result<(), AppError> {
         let (client, _) = server.accept()?;
         client.set_nonblocking(true)?;
@@ -103,76 +174,96 @@ impl App {
             client: TcpStream::from_std(client),
             server: None,
             state: State::Init,
+            token: WAKER,
+            client_registered: false,
+            server_registered: false,
+            addrs: Vec::new(),
+            addr_index: 0,
+            mode: Mode::Get,
+            header_buf: Vec::new(),
+            request_len: 0,
+            consumed: 0,
+            c2s: Vec::new(),
+            s2c: Vec::new(),
+            client_eof: false,
+            server_eof: false,
+            ack_sent: false,
         };
 
-        sender.send(connection).map_err(|_| AppError::Unknown)
+        sender.send(connection).map_err(|_| AppError::Unknown)?;
+        waker.wake()?;
+        Ok(())
     }
 }
 
 struct Worker<'a> {
     app: &'a App,
     receiver: Receiver<Connection<'a>>,
-    connections: Vec<Connection<'a>>,
+    poll: Poll,
+    connections: HashMap<Token, Connection<'a>>,
+    next_id: usize,
 }
 
 impl<'a> Worker<'a> {
-    pub fn run(app: &'a App, receiver: Receiver<Connection<'a>>) {
+    pub fn run(app: &'a App, receiver: Receiver<Connection<'a>>, poll: Poll) {
         let mut worker = Self {
             app,
             receiver,
-            connections: Vec::new(),
+            poll,
+            connections: HashMap::new(),
+            next_id: 0,
         };
 
+        if let Err(e) = worker.event_loop() {
+            err(e);
+        }
+    }
+
+    fn event_loop(&mut self) -> Result<(), AppError> {
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+
         loop {
-            thread::sleep(WORKER_DELAY);
+            self.poll.poll(&mut events, None)?;
 
-            if let Err(e) = worker.handle_connections() {
-                err(e);
-                return;
+            for event in events.iter() {
+                if event.token() == WAKER {
+                    self.take_connections();
+                    continue;
+                }
+                self.drive(base_token(event));
             }
         }
     }
 
-    fn handle_connections(&mut self) -> Result<(), RecvError> {
-        if self.connections.is_empty() {
-            self.connections.push(self.receiver.recv()?);
-        } else {
-            while let Ok(connection) = self.receiver.try_recv() {
-                self.connections.push(connection);
-            }
+    fn take_connections(&mut self) {
+        while let Ok(mut connection) = self.receiver.try_recv() {
+            let token = Token(self.next_id << 1);
+            self.next_id += 1;
+            connection.token = token;
+            self.connections.insert(token, connection);
+            self.drive(token);
         }
+    }
 
-        let mut index = 0;
+    fn drive(&mut self, token: Token) {
+        let debug = self.app.debug;
+        let registry = self.poll.registry();
 
-        while index < self.connections.len() {
-            if let Some(connection) = self.connections.get_mut(index) {
-                let done = connection.progress().unwrap_or_else(|e| {
-                    if self.app.debug {
-                        err(e);
-                    }
-                    true
-                });
-
-                if !done {
-                    index += 1;
-                    continue;
+        let done = match self.connections.get_mut(&token) {
+            Some(connection) => connection.progress(registry).unwrap_or_else(|e| {
+                if debug {
+                    err(e);
                 }
-            } else {
-                break;
-            }
-
-            let Some(last) = self.connections.pop() else {
-                break;
-            };
+                true
+            }),
+            None => return,
+        };
 
-            if index >= self.connections.len() {
-                break;
+        if done {
+            if let Some(mut connection) = self.connections.remove(&token) {
+                connection.close(registry);
             }
-
-            self.connections[index] = last;
         }
-
-        Ok(())
     }
 }
 
@@ -181,53 +272,165 @@ struct Connection<'a> {
     client: TcpStream,
     server: Option<TcpStream>,
     state: State,
+    token: Token,
+    client_registered: bool,
+    server_registered: bool,
+    addrs: Vec<SocketAddr>,
+    addr_index: usize,
+    mode: Mode,
+    header_buf: Vec<u8>,
+    request_len: usize,
+    consumed: usize,
+    // Bytes read from one side but not yet flushed to the other, held here when
+    // the destination socket is under write backpressure.
+    c2s: Vec<u8>,
+    s2c: Vec<u8>,
+    client_eof: bool,
+    server_eof: bool,
+    ack_sent: bool,
 }
 
 enum State {
     Init,
     Conn,
+    Handshake,
     Send,
     Recv,
+    Relay,
     Done,
 }
 
+// How the upstream traffic is carried once the socket is open.
+enum Mode {
+    // Plain forwarding of an HTTP request and its response.
+    Get,
+    // CONNECT tunnel to a direct target: answer the client ourselves, then relay.
+    ConnectDirect,
+    // CONNECT tunnel through the upstream proxy: forward the request untouched.
+    ConnectProxy,
+}
+
 impl<'a> Connection<'a> {
-    fn progress(&mut self) -> Result<bool, ConnError> {
+    fn progress(&mut self, registry: &Registry) -> Result<bool, ConnError> {
         use State::*;
 
         while match self.state {
-            Init => self.init()?,
-            Conn => self.connect()?,
-            Send => self.send()?,
-            Recv => self.recv()?,
+            Init => self.init(registry)?,
+            Conn => self.connect(registry)?,
+            Handshake => self.handshake(registry)?,
+            Send => self.send(registry)?,
+            Recv => self.recv(registry)?,
+            Relay => self.relay(registry)?,
             Done => return Ok(true),
         } {}
 
         Ok(false)
     }
 
-    fn init(&mut self) -> Result<bool, ConnError> {
-        let mut buf = uninit_buffer::<BUFFER_SIZE>();
+    fn init(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        let max = self.app.max_header_size;
+
+        // Peek the request without consuming it (the forwarding states still
+        // read it off the socket), growing the window until the full header
+        // block is buffered or the client has sent everything it has so far.
+        let request_len = loop {
+            if self.header_buf.is_empty() {
+                self.header_buf.resize(BUFFER_SIZE.min(max), 0);
+            }
+
+            let count = match self.client.peek(&mut self.header_buf) {
+                // Client closed before sending a complete request; nothing to route.
+                Ok(0) => E!(ConnError::NotHttp),
+                Ok(count) => count,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.watch_client(registry, Interest::READABLE)?;
+                    return Ok(false);
+                }
+                Err(e) => E!(e),
+            };
+
+            if let Some(end) = header_end(&self.header_buf[..count]) {
+                break end;
+            }
+
+            // The window filled up, so more bytes may be waiting: grow and retry.
+            if count == self.header_buf.len() && self.header_buf.len() < max {
+                let grow = (self.header_buf.len() * 2).min(max);
+                self.header_buf.resize(grow, 0);
+                continue;
+            }
+
+            // Headers exceed the allowed size without terminating: refuse.
+            if count >= max {
+                E!(ConnError::ParseError);
+            }
 
-        let Ok(count) = self.client.peek(buf.as_mut_slice()) else {
+            // Request is still incomplete; wait for the client to send more.
+            self.watch_client(registry, Interest::READABLE)?;
             return Ok(false);
         };
 
-        let data = &mut buf[..count];
+        let view = &self.header_buf[..request_len];
 
-        if !check_http(data) {
+        if !check_http(view) {
             E!(ConnError::NotHttp);
         }
 
-        self.server = Some(TcpStream::connect(self.resolve(data)?)?);
+        let connect = is_connect(view);
+        let mut data = view.to_vec();
+        let (addrs, proxy) = self.resolve(data.as_mut_slice(), connect)?;
+
+        self.mode = match (connect, proxy) {
+            (false, _) => Mode::Get,
+            (true, true) => Mode::ConnectProxy,
+            (true, false) => Mode::ConnectDirect,
+        };
+        self.request_len = request_len;
+        self.addrs = addrs;
+        self.addr_index = 0;
+        self.dial(registry)?;
         self.state = State::Conn;
         Ok(true)
     }
 
-    fn resolve(&self, data: &mut [u8]) -> Result<SocketAddr, ConnError> {
-        let host = {
-            let content = str::from_utf8_mut(data)?;
-            content.make_ascii_lowercase();
+    // Open the upstream socket to the current candidate address.
+    fn dial(&mut self, registry: &Registry) -> Result<(), ConnError> {
+        let addr = *self.addrs.get(self.addr_index).ok_or(ConnError::DnsError)?;
+        self.server = Some(TcpStream::connect(addr)?);
+        self.server_registered = false;
+        self.watch_server(registry, Interest::WRITABLE)?;
+        Ok(())
+    }
+
+    // Drop the dead upstream socket and retry with the next resolved address,
+    // giving up with `DnsError` once every candidate has been tried.
+    fn advance(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        if let Some(mut server) = self.server.take() {
+            let _ = registry.deregister(&mut server);
+        }
+
+        self.addr_index += 1;
+        if self.addr_index >= self.addrs.len() {
+            E!(ConnError::DnsError);
+        }
+
+        self.dial(registry)?;
+        Ok(false)
+    }
+
+    fn resolve(&self, data: &mut [u8], connect: bool) -> Result<(Vec<SocketAddr>, bool), ConnError> {
+        let content = str::from_utf8_mut(data)?;
+        content.make_ascii_lowercase();
+
+        // CONNECT carries the authority in the request line; everything else
+        // is routed on the Host header.
+        let host = if connect {
+            content
+                .lines()
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .ok_or(ConnError::ParseError)?
+        } else {
             content
                 .lines()
                 .map(|s| s.trim_start())
@@ -236,66 +439,188 @@ impl<'a> Connection<'a> {
                 .trim()
         };
 
-        if self.app.hosts.contains(host) {
+        // The CONNECT authority always carries a port; match the routing set on
+        // the bare host but dial the full `host:port`.
+        let name = if connect { strip_port(host) } else { host };
+        self.route(name, host)
+    }
+
+    fn route(&self, name: &str, host: &str) -> Result<(Vec<SocketAddr>, bool), ConnError> {
+        if self.app.hosts.matches(name) {
             if self.app.debug {
-                println!("{host} => PROXY");
+                println!("{name} => PROXY");
             }
-            return Ok(self.app.proxy);
+            return Ok((vec![self.app.proxy], true));
         }
 
         if self.app.debug {
-            println!("{host} => DIRECT");
+            println!("{name} => DIRECT");
         }
 
-        self.app.dns.resolve(host)
+        Ok((self.app.dns.resolve(host)?, false))
     }
 
-    fn connect(&mut self) -> Result<bool, ConnError> {
-        let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
-
-        if let Err(e) = server.peer_addr() {
-            if matches!(e.kind(), ErrorKind::NotConnected | ErrorKind::WouldBlock) {
-                return Ok(false);
+    fn connect(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        let token = self.server_token();
+
+        let failed = {
+            let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+
+            // A failed non-blocking connect surfaces through SO_ERROR, not
+            // peer_addr() (which reports NotConnected while still pending), so
+            // inspect it first: Some(err) means this candidate is dead.
+            match server.take_error() {
+                Ok(Some(_)) | Err(_) => true,
+                Ok(None) => match server.peer_addr() {
+                    Ok(_) => false,
+                    Err(e) if matches!(e.kind(), ErrorKind::NotConnected | ErrorKind::WouldBlock) => {
+                        registry.reregister(server, token, Interest::WRITABLE)?;
+                        return Ok(false);
+                    }
+                    Err(_) => true,
+                },
             }
-            E!(e);
+        };
+
+        if failed {
+            return self.advance(registry);
         }
 
+        let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+
         if let Err(e) = server.set_nodelay(true) {
             if e.kind() == ErrorKind::InvalidInput {
+                registry.reregister(server, token, Interest::WRITABLE)?;
                 return Ok(false);
             }
             E!(e);
         }
 
-        self.state = State::Send;
+        // The upstream socket is live; pick the transfer path for this request.
+        self.state = match self.mode {
+            Mode::Get => State::Send,
+            Mode::ConnectProxy => State::Relay,
+            Mode::ConnectDirect => State::Handshake,
+        };
         Ok(true)
     }
 
-    fn send(&mut self) -> Result<bool, ConnError> {
-        let mut buf = uninit_buffer::<BUFFER_SIZE>();
+    fn handshake(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        // Consume exactly the CONNECT request parsed in init() so any bytes the
+        // client pipelined after it (e.g. a TLS ClientHello) stay in the socket
+        // for the relay instead of being dropped or leaked to the origin.
+        while self.consumed < self.request_len {
+            let want = (self.request_len - self.consumed).min(BUFFER_SIZE);
+            let mut buf = uninit_buffer::<BUFFER_SIZE>();
+
+            match self.client.read(&mut buf[..want]) {
+                Ok(0) => break,
+                Ok(count) => self.consumed += count,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.watch_client(registry, Interest::READABLE)?;
+                    return Ok(false);
+                }
+                Err(e) => E!(e),
+            }
+        }
+
+        // Acknowledge the tunnel, tolerating a short write on a full send buffer.
+        if !self.ack_sent {
+            self.s2c.extend_from_slice(ESTABLISHED);
+            self.ack_sent = true;
+        }
 
-        let Ok(count) = self.client.read(buf.as_mut_slice()) else {
+        if !flush(&mut self.client, &mut self.s2c)? {
+            self.watch_client(registry, Interest::WRITABLE)?;
             return Ok(false);
+        }
+
+        self.state = State::Relay;
+        Ok(true)
+    }
+
+    fn relay(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        {
+            let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+            pump(&mut self.client, server, &mut self.c2s, &mut self.client_eof)?;
+            pump(server, &mut self.client, &mut self.s2c, &mut self.server_eof)?;
+        }
+
+        // Finish only once both halves have closed and drained; a single EOF
+        // leaves the opposite direction free to keep flowing (half-close).
+        if self.client_eof && self.server_eof && self.c2s.is_empty() && self.s2c.is_empty() {
+            self.state = State::Done;
+            return Ok(true);
+        }
+
+        self.rearm(registry)
+    }
+
+    // Re-arm edge-triggered interest from what each side still has to do: read
+    // while its source is open, write while the peer has buffered bytes pending.
+    fn rearm(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        if let Some(interest) = interest(!self.client_eof, !self.s2c.is_empty()) {
+            self.watch_client(registry, interest)?;
+        }
+
+        if let Some(interest) = interest(!self.server_eof, !self.c2s.is_empty()) {
+            self.watch_server(registry, interest)?;
+        }
+
+        Ok(false)
+    }
+
+    fn send(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        // Drain anything still queued to the server before reading more.
+        {
+            let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+            if !flush(server, &mut self.c2s)? {
+                self.watch_server(registry, Interest::WRITABLE)?;
+                return Ok(false);
+            }
+        }
+
+        let mut buf = uninit_buffer::<BUFFER_SIZE>();
+
+        let count = match self.client.read(buf.as_mut_slice()) {
+            Ok(count) => count,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                self.watch_client(registry, Interest::READABLE)?;
+                return Ok(false);
+            }
+            Err(e) => E!(e),
         };
 
         if count == 0 {
+            self.watch_server(registry, Interest::READABLE)?;
             self.state = State::Recv;
             return Ok(true);
         }
 
-        self.server
-            .as_mut()
-            .ok_or(ConnError::Unknown)?
-            .write_all(&buf[..count])?;
+        {
+            let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+            self.c2s.extend_from_slice(&buf[..count]);
+            if !flush(server, &mut self.c2s)? {
+                self.watch_server(registry, Interest::WRITABLE)?;
+                return Ok(false);
+            }
+        }
 
         if count < buf.len() {
+            self.watch_server(registry, Interest::READABLE)?;
             self.state = State::Recv;
         }
 
         Ok(true)
     }
 
-    fn recv(&mut self) -> Result<bool, ConnError> {
+    fn recv(&mut self, registry: &Registry) -> Result<bool, ConnError> {
+        // Drain the queued response to the client before reading more.
+        if !flush(&mut self.client, &mut self.s2c)? {
+            self.watch_client(registry, Interest::WRITABLE)?;
+            return Ok(false);
+        }
+
         if match self.client.peek(uninit_buffer::<1>().as_mut_slice()) {
             Ok(c) => c == 0,
             Err(e) => e.kind() != ErrorKind::WouldBlock,
@@ -305,26 +630,158 @@ impl<'a> Connection<'a> {
         }
 
         let mut buf = uninit_buffer::<BUFFER_SIZE>();
+        let token = self.server_token();
+        let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
 
-        let Ok(count) = self
-            .server
-            .as_mut()
-            .ok_or(ConnError::Unknown)?
-            .read(buf.as_mut_slice())
-            else { return Ok(false); };
+        let count = match server.read(buf.as_mut_slice()) {
+            Ok(count) => count,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                registry.reregister(server, token, Interest::READABLE)?;
+                return Ok(false);
+            }
+            Err(e) => E!(e),
+        };
 
         if count == 0 {
             self.state = State::Done;
             return Ok(true);
         }
 
-        self.client.write_all(&buf[..count])?;
+        self.s2c.extend_from_slice(&buf[..count]);
+        if !flush(&mut self.client, &mut self.s2c)? {
+            self.watch_client(registry, Interest::WRITABLE)?;
+            return Ok(false);
+        }
+
         Ok(true)
     }
+
+    fn server_token(&self) -> Token {
+        Token(self.token.0 | 1)
+    }
+
+    fn watch_client(&mut self, registry: &Registry, interest: Interest) -> Result<(), ConnError> {
+        let token = self.token;
+        if self.client_registered {
+            registry.reregister(&mut self.client, token, interest)?;
+        } else {
+            registry.register(&mut self.client, token, interest)?;
+            self.client_registered = true;
+        }
+        Ok(())
+    }
+
+    fn watch_server(&mut self, registry: &Registry, interest: Interest) -> Result<(), ConnError> {
+        let token = self.server_token();
+        let server = self.server.as_mut().ok_or(ConnError::Unknown)?;
+        if self.server_registered {
+            registry.reregister(server, token, interest)?;
+        } else {
+            registry.register(server, token, interest)?;
+            self.server_registered = true;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, registry: &Registry) {
+        let _ = registry.deregister(&mut self.client);
+        if let Some(server) = self.server.as_mut() {
+            let _ = registry.deregister(server);
+        }
+    }
+}
+
+fn base_token(event: &Event) -> Token {
+    Token(event.token().0 & !1)
 }
 
 fn check_http(data: &[u8]) -> bool {
-    (data.len() > GET.len()) && (&data[..GET.len()] == GET)
+    method_is(data, GET) || method_is(data, CONNECT)
+}
+
+fn is_connect(data: &[u8]) -> bool {
+    method_is(data, CONNECT)
+}
+
+// Host portion of an authority, dropping a trailing `:port` (and honoring
+// bracketed IPv6 literals like `[::1]:443`).
+fn strip_port(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(authority);
+    }
+
+    authority
+        .rsplit_once(':')
+        .map_or(authority, |(host, _)| host)
+}
+
+// Offset just past the blank line that ends the request headers, if present.
+fn header_end(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+fn method_is(data: &[u8], method: &[u8]) -> bool {
+    (data.len() > method.len()) && (&data[..method.len()] == method)
+}
+
+// Write as much of `pending` to `dst` as the send buffer accepts, dropping what
+// was written from it. Returns `true` once everything has been flushed; a non-blocking socket
+// that is full simply leaves the remainder queued for the next WRITABLE wake-up.
+fn flush(dst: &mut TcpStream, pending: &mut Vec<u8>) -> Result<bool, ConnError> {
+    let mut written = 0;
+
+    while written < pending.len() {
+        match dst.write(&pending[written..]) {
+            Ok(0) => break,
+            Ok(count) => written += count,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => E!(e),
+        }
+    }
+
+    pending.drain(..written);
+    Ok(pending.is_empty())
+}
+
+// Move everything currently available from `src` to `dst`, queueing any bytes
+// that `dst` cannot accept yet in `pending`. Stops on write backpressure so the
+// caller can wait for WRITABLE; records end of stream in `eof`.
+fn pump(
+    src: &mut TcpStream,
+    dst: &mut TcpStream,
+    pending: &mut Vec<u8>,
+    eof: &mut bool,
+) -> Result<(), ConnError> {
+    if !flush(dst, pending)? {
+        return Ok(());
+    }
+
+    while !*eof {
+        let mut buf = uninit_buffer::<BUFFER_SIZE>();
+        match src.read(buf.as_mut_slice()) {
+            Ok(0) => *eof = true,
+            Ok(count) => {
+                pending.extend_from_slice(&buf[..count]);
+                if !flush(dst, pending)? {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => E!(e),
+        }
+    }
+
+    Ok(())
+}
+
+// Interest for a socket that may want to read, write, both, or neither.
+fn interest(readable: bool, writable: bool) -> Option<Interest> {
+    match (readable, writable) {
+        (true, true) => Some(Interest::READABLE | Interest::WRITABLE),
+        (true, false) => Some(Interest::READABLE),
+        (false, true) => Some(Interest::WRITABLE),
+        (false, false) => None,
+    }
 }
 
 fn uninit_buffer<const N: usize>() -> [u8; N] {
@@ -335,40 +792,96 @@ fn uninit_buffer<const N: usize>() -> [u8; N] {
     }
 }
 
-type DnsCache = HashMap<String, SocketAddr>;
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    inserted: Instant,
+    used: u64,
+}
+
+// Least-recently-used cache with per-entry expiry. `used` orders entries for
+// eviction; `inserted` bounds their lifetime against the configured TTL.
+struct DnsCache {
+    entries: HashMap<String, Entry>,
+    tick: u64,
+}
+
+impl DnsCache {
+    fn new() -> Self {
+        DnsCache {
+            entries: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, host: &str, ttl: Duration) -> Option<Vec<SocketAddr>> {
+        let expired = match self.entries.get(host) {
+            Some(entry) => entry.inserted.elapsed() >= ttl,
+            None => return None,
+        };
+
+        if expired {
+            self.entries.remove(host);
+            return None;
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(host)?;
+        entry.used = tick;
+        Some(entry.addrs.clone())
+    }
+
+    fn insert(&mut self, host: &str, addrs: Vec<SocketAddr>, max_size: usize) {
+        if max_size > 0 && !self.entries.contains_key(host) && self.entries.len() >= max_size {
+            self.evict_lru();
+        }
+
+        self.tick += 1;
+        self.entries.insert(
+            host.to_owned(),
+            Entry {
+                addrs,
+                inserted: Instant::now(),
+                used: self.tick,
+            },
+        );
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some(key) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| key.clone())
+        {
+            self.entries.remove(&key);
+        }
+    }
+}
 
 struct Dns {
+    ttl: Duration,
+    max_size: usize,
     cache: Mutex<DnsCache>,
 }
 
 impl Dns {
-    pub fn new() -> Self {
+    pub fn new(ttl: Duration, max_size: usize) -> Self {
         Dns {
-            cache: Mutex::new(HashMap::new()),
+            ttl,
+            max_size,
+            cache: Mutex::new(DnsCache::new()),
         }
     }
 
-    pub fn resolve(&self, host: &str) -> Result<SocketAddr, ConnError> {
-        if let Some(cached) = self.cache()?.get(host) {
-            return Ok(*cached);
+    pub fn resolve(&self, host: &str) -> Result<Vec<SocketAddr>, ConnError> {
+        if let Some(cached) = self.cache()?.get(host, self.ttl) {
+            return Ok(cached);
         }
 
-        let mut resolved = {
-            let v6 = host.starts_with('[');
-            if (v6 && host.contains("]:")) || (!v6 && host.contains(':')) {
-                host.to_socket_addrs()
-            } else {
-                (host, 80).to_socket_addrs()
-            }
-            .map_err(|_| ConnError::DnsError)?
-        };
-
-        if let Some(addr) = resolved.next() {
-            self.cache()?.insert(host.to_owned(), addr);
-            return Ok(addr);
-        }
-
-        E!(ConnError::DnsError);
+        let addrs = resolve_host(host)?;
+        self.cache()?.insert(host, addrs.clone(), self.max_size);
+        Ok(addrs)
     }
 
     fn cache(&self) -> Result<MutexGuard<DnsCache>, ConnError> {
@@ -378,3 +891,20 @@ impl Dns {
         }
     }
 }
+
+fn resolve_host(host: &str) -> Result<Vec<SocketAddr>, ConnError> {
+    let v6 = host.starts_with('[');
+    let resolved = if (v6 && host.contains("]:")) || (!v6 && host.contains(':')) {
+        host.to_socket_addrs()
+    } else {
+        (host, 80).to_socket_addrs()
+    }
+    .map_err(|_| ConnError::DnsError)?;
+
+    let addrs = resolved.collect::<Vec<_>>();
+    if addrs.is_empty() {
+        E!(ConnError::DnsError);
+    }
+
+    Ok(addrs)
+}
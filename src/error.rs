@@ -113,7 +113,7 @@ impl Display for ConnError {
     fn fmt(&self, f: &mut Formatter) -> Result {
         use ConnError::*;
         match self {
-            NotHttp => write!(f, "not HTTP GET request"),
+            NotHttp => write!(f, "not an HTTP GET or CONNECT request"),
             ParseError => write!(f, "unable to parse request"),
             DnsError => write!(f, "unable to resolve host"),
             Unknown => write!(f, "an unknown error occured"),
@@ -1,4 +1,4 @@
-use std::{env, net::ToSocketAddrs, process::ExitCode};
+use std::{env, net::ToSocketAddrs, process::ExitCode, time::Duration};
 
 mod error;
 use error::*;
@@ -21,6 +21,9 @@ fn start() -> Result<(), MainError> {
     let mut hosts = Hosts::new();
     let mut worker_threads = 1;
     let mut debug = false;
+    let mut dns_ttl = Duration::from_secs(600);
+    let mut dns_cache_size = 1024;
+    let mut max_header_size = 16384;
     let mut args = env::args().skip(1);
 
     while let Some(arg) = args.next() {
@@ -48,11 +51,22 @@ fn start() -> Result<(), MainError> {
                 listen_port = parse!(next!().parse());
             }
             "-h" => {
-                hosts.extend(next!().split(',').map(|s| s.trim().to_owned()));
+                for pattern in next!().split(',') {
+                    hosts.add(pattern);
+                }
             }
             "-t" => {
                 worker_threads = parse!(next!().parse());
             }
+            "--dns-ttl" => {
+                dns_ttl = Duration::from_secs(parse!(next!().parse()));
+            }
+            "--dns-cache-size" => {
+                dns_cache_size = parse!(next!().parse());
+            }
+            "--max-header-size" => {
+                max_header_size = parse!(next!().parse());
+            }
             "-d" => {
                 debug = true;
             }
@@ -70,6 +84,17 @@ fn start() -> Result<(), MainError> {
         E!(ArgError::NoHosts);
     }
 
-    App::start(proxy, hosts, listen_port, worker_threads, debug)?;
+    App::start(
+        proxy,
+        hosts,
+        listen_port,
+        worker_threads,
+        debug,
+        Config {
+            dns_ttl,
+            dns_cache_size,
+            max_header_size,
+        },
+    )?;
     Ok(())
 }